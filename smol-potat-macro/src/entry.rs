@@ -0,0 +1,390 @@
+//! Shared attribute parsing and code generation for the `main`, `test` and
+//! `bench` entry-point macros.
+//!
+//! Keeping the knob parsing and the executor scaffolding here, rather than
+//! duplicated across the three macros in `lib.rs`, gives span-accurate
+//! diagnostics (errors point at the offending attribute token instead of
+//! being interpolated into a generic message) and a single place to add
+//! future knobs.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+
+/// How many executor threads an entry point should spawn.
+pub(crate) enum Threads {
+    /// A fixed, user-specified number of executor threads.
+    Fixed(u32),
+    /// Detect the number of threads to spawn from
+    /// `std::thread::available_parallelism()` at runtime.
+    Auto,
+}
+
+/// The attribute arguments accepted by `#[smol_potat::main]`, `#[smol_potat::test]`
+/// and `#[smol_potat::bench]`.
+#[derive(Default)]
+pub(crate) struct Knobs {
+    pub(crate) threads: Option<Threads>,
+    pub(crate) max_blocking_threads: Option<u32>,
+    pub(crate) thread_name: Option<String>,
+    pub(crate) thread_stack_size: Option<usize>,
+}
+
+/// Parses and validates the attribute arguments shared by all three
+/// `smol_potat` entry-point macros, reporting span-accurate diagnostics
+/// through `syn::Error` instead of interpolating them into `compile_error!`.
+pub(crate) fn build_knobs(args: syn::AttributeArgs) -> Result<Knobs, syn::Error> {
+    let mut knobs = Knobs::default();
+
+    for arg in args {
+        match arg {
+            syn::NestedMeta::Meta(syn::Meta::NameValue(namevalue)) => {
+                let ident = namevalue.path.get_ident().ok_or_else(|| {
+                    syn::Error::new_spanned(&namevalue.path, "Must have specified ident")
+                })?;
+                match ident.to_string().to_lowercase().as_str() {
+                    "threads" => match &namevalue.lit {
+                        syn::Lit::Int(expr) => {
+                            let num = expr.base10_parse::<u32>()?;
+                            if num > 1 {
+                                knobs.threads = Some(Threads::Fixed(num));
+                            }
+                        }
+                        syn::Lit::Str(s) if s.value() == "auto" => {
+                            knobs.threads = Some(Threads::Auto);
+                        }
+                        lit => {
+                            return Err(syn::Error::new_spanned(
+                                lit,
+                                "threads argument must be an int or \"auto\"",
+                            ));
+                        }
+                    },
+                    "max_blocking_threads" => match &namevalue.lit {
+                        syn::Lit::Int(expr) => {
+                            let num = expr.base10_parse::<u32>()?;
+                            if num == 0 {
+                                return Err(syn::Error::new_spanned(
+                                    &namevalue.lit,
+                                    "max_blocking_threads argument must be positive",
+                                ));
+                            }
+                            knobs.max_blocking_threads = Some(num);
+                        }
+                        lit => {
+                            return Err(syn::Error::new_spanned(
+                                lit,
+                                "max_blocking_threads argument must be an int",
+                            ));
+                        }
+                    },
+                    "thread_name" => match &namevalue.lit {
+                        syn::Lit::Str(s) => {
+                            knobs.thread_name = Some(s.value());
+                        }
+                        lit => {
+                            return Err(syn::Error::new_spanned(
+                                lit,
+                                "thread_name argument must be a string",
+                            ));
+                        }
+                    },
+                    "thread_stack_size" => match &namevalue.lit {
+                        syn::Lit::Int(expr) => {
+                            let num = expr.base10_parse::<usize>()?;
+                            if num == 0 {
+                                return Err(syn::Error::new_spanned(
+                                    &namevalue.lit,
+                                    "thread_stack_size argument must be positive",
+                                ));
+                            }
+                            knobs.thread_stack_size = Some(num);
+                        }
+                        lit => {
+                            return Err(syn::Error::new_spanned(
+                                lit,
+                                "thread_stack_size argument must be an int",
+                            ));
+                        }
+                    },
+                    _ => {
+                        return Err(syn::Error::new_spanned(
+                            &namevalue.path,
+                            format!(
+                                "Unknown attribute pair `{}` is specified; expected one of: \
+                                 `threads`, `max_blocking_threads`, `thread_name`, `thread_stack_size`",
+                                ident
+                            ),
+                        ));
+                    }
+                }
+            }
+            other => {
+                return Err(syn::Error::new_spanned(
+                    &other,
+                    "Unknown attribute inside the macro",
+                ));
+            }
+        }
+    }
+
+    Ok(knobs)
+}
+
+/// Emits the `if BLOCKING_MAX_THREADS is unset { set_var(...) }` prelude for
+/// `knobs.max_blocking_threads`, or nothing if the knob wasn't specified.
+pub(crate) fn set_max_blocking_threads(knobs: &Knobs) -> TokenStream2 {
+    match knobs.max_blocking_threads {
+        Some(num) => quote! {
+            if std::env::var("BLOCKING_MAX_THREADS").is_err() {
+                std::env::set_var("BLOCKING_MAX_THREADS", #num.to_string());
+            }
+        },
+        None => quote! {},
+    }
+}
+
+/// Whether `knobs` asked for named and/or sized executor threads, in which
+/// case the `std::thread::Builder`-based scaffolding is needed instead of
+/// the plain `easy_parallel` default.
+pub(crate) fn wants_named_threads(knobs: &Knobs) -> bool {
+    knobs.thread_name.is_some() || knobs.thread_stack_size.is_some()
+}
+
+/// Builds the `easy_parallel`-based scaffolding that spawns `count`
+/// anonymous executor threads driving `ex`, runs `body_future` on the
+/// current thread, and returns its result. This is the default multi-thread
+/// path and matches the executor/MSRV baseline when no thread knobs are set.
+fn spawn_easy_parallel(count: TokenStream2, body_future: TokenStream2) -> TokenStream2 {
+    quote! {
+        let (_, r) = smol_potat::easy_parallel::Parallel::new()
+            .each(0..#count, |_| smol_potat::futures_lite::future::block_on(ex.run(shutdown.recv())))
+            .finish(|| smol_potat::futures_lite::future::block_on(async {
+                let r = #body_future;
+                drop(signal);
+                r
+            }));
+
+        r
+    }
+}
+
+/// Builds the `std::thread::scope` + `std::thread::Builder` scaffolding that
+/// spawns `count` named/sized executor threads driving `ex`, runs
+/// `body_future` on the current thread, and returns its result. Only used
+/// when `knobs.thread_name` or `knobs.thread_stack_size` is set, since it
+/// requires `std::thread::scope` (stable since Rust 1.63).
+fn spawn_executor_threads(knobs: &Knobs, count: TokenStream2, body_future: TokenStream2) -> TokenStream2 {
+    let worker_name = knobs
+        .thread_name
+        .clone()
+        .unwrap_or_else(|| "smol-potat".to_string());
+    let stack_size_call = match knobs.thread_stack_size {
+        Some(size) => quote! { .stack_size(#size) },
+        None => quote! {},
+    };
+
+    quote! {
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..#count)
+                .map(|i| {
+                    std::thread::Builder::new()
+                        .name(format!("{}-{}", #worker_name, i))
+                        #stack_size_call
+                        .spawn_scoped(scope, || smol_potat::futures_lite::future::block_on(ex.run(shutdown.recv())))
+                        .expect("failed to spawn executor thread")
+                })
+                .collect();
+
+            let r = smol_potat::futures_lite::future::block_on(async {
+                let r = #body_future;
+                drop(signal);
+                r
+            });
+
+            for handle in handles {
+                let _ = handle.join().expect("executor thread panicked");
+            }
+
+            r
+        })
+    }
+}
+
+/// Picks between [`spawn_executor_threads`] and [`spawn_easy_parallel`]
+/// depending on whether `knobs` asked for named/sized threads, so that
+/// opting into `thread_name`/`thread_stack_size` is the only thing that
+/// pulls in the `std::thread::scope`-based scaffolding.
+pub(crate) fn spawn_threads(knobs: &Knobs, count: TokenStream2, body_future: TokenStream2) -> TokenStream2 {
+    if wants_named_threads(knobs) {
+        spawn_executor_threads(knobs, count, body_future)
+    } else {
+        spawn_easy_parallel(count, body_future)
+    }
+}
+
+/// Runs `body` to completion on a single named/sized thread, for entry
+/// points that asked for `thread_name`/`thread_stack_size` but not multiple
+/// executor threads (e.g. the default `threads = 1` behavior). `body` must
+/// be a complete expression that produces the entry point's return value.
+///
+/// Spawned through `std::thread::scope` rather than a plain `thread::spawn`
+/// so `body` may borrow locals (such as `bench`'s `&mut test::Bencher`)
+/// instead of requiring everything it touches to be `'static`.
+///
+/// Only called from the `None` arms of `parse_knobs` below and of `bench` in
+/// `lib.rs`, which are themselves gated on
+/// `not(feature = "auto")`, so this is unused (and would warn as dead code)
+/// when that feature is enabled.
+#[cfg(not(feature = "auto"))]
+pub(crate) fn spawn_single_thread(knobs: &Knobs, body: TokenStream2) -> TokenStream2 {
+    let worker_name = knobs
+        .thread_name
+        .clone()
+        .unwrap_or_else(|| "smol-potat".to_string());
+    let stack_size_call = match knobs.thread_stack_size {
+        Some(size) => quote! { .stack_size(#size) },
+        None => quote! {},
+    };
+
+    quote! {
+        std::thread::scope(|scope| {
+            std::thread::Builder::new()
+                .name(#worker_name.to_string())
+                #stack_size_call
+                .spawn_scoped(scope, || #body)
+                .expect("failed to spawn entry-point thread")
+                .join()
+                .expect("entry-point thread panicked")
+        })
+    }
+}
+
+/// Parses and validates `args`, then rewrites `input` into the generated
+/// entry point according to the parsed knobs. `is_test` selects between the
+/// plain `fn main` wrapper (used by `#[smol_potat::main]`) and the
+/// `#[test] fn #name` wrapper (used by `#[smol_potat::test]`).
+pub(crate) fn parse_knobs(
+    input: syn::ItemFn,
+    args: syn::AttributeArgs,
+    is_test: bool,
+) -> Result<TokenStream, syn::Error> {
+    if input.sig.asyncness.is_none() {
+        let msg = "the async keyword is missing from the function declaration";
+        return Err(syn::Error::new_spanned(input.sig.fn_token, msg));
+    }
+
+    let knobs = build_knobs(args)?;
+
+    let ret = &input.sig.output;
+    let inputs = &input.sig.inputs;
+    let name = &input.sig.ident;
+    let body = &input.block;
+    let attrs = &input.attrs;
+
+    let set_max_blocking_threads = set_max_blocking_threads(&knobs);
+
+    let (header, fn_name, inner_fn, body_future) = if is_test {
+        (quote! { #[test] }, quote! { #name }, quote! {}, quote! { #body })
+    } else {
+        (
+            quote! {},
+            quote! { main },
+            quote! {
+                #(#attrs)*
+                async fn main(#inputs) #ret {
+                    #body
+                }
+            },
+            quote! { main().await },
+        )
+    };
+    let outer_attrs = if is_test { quote! { #(#attrs)* } } else { quote! {} };
+
+    let result = match knobs.threads {
+        Some(Threads::Fixed(num)) => {
+            let threads_block = spawn_threads(&knobs, quote! { #num }, body_future.clone());
+            quote! {
+                #header
+                #outer_attrs
+                fn #fn_name() #ret {
+                    #set_max_blocking_threads
+                    #inner_fn
+
+                    let ex = smol_potat::async_executor::Executor::new();
+                    let (signal, shutdown) = smol_potat::async_channel::unbounded::<()>();
+
+                    #threads_block
+                }
+            }
+        }
+        Some(Threads::Auto) => {
+            let threads_block = spawn_threads(&knobs, quote! { auto_threads }, body_future.clone());
+            quote! {
+                #header
+                #outer_attrs
+                fn #fn_name() #ret {
+                    #set_max_blocking_threads
+                    #inner_fn
+
+                    let ex = smol_potat::async_executor::Executor::new();
+                    let (signal, shutdown) = smol_potat::async_channel::unbounded::<()>();
+
+                    // `threads = "auto"` is resolved entirely through `std`, unlike the
+                    // legacy `auto` Cargo feature below, so it works without relying on
+                    // `smol_potat` re-exporting `num_cpus`.
+                    let auto_threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+
+                    #threads_block
+                }
+            }
+        }
+        #[cfg(feature = "auto")]
+        None => {
+            let threads_block = spawn_threads(&knobs, quote! { num_cpus }, body_future.clone());
+            quote! {
+                #header
+                #outer_attrs
+                fn #fn_name() #ret {
+                    #set_max_blocking_threads
+                    #inner_fn
+
+                    let ex = smol_potat::async_executor::Executor::new();
+                    let (signal, shutdown) = smol_potat::async_channel::unbounded::<()>();
+
+                    let num_cpus = smol_potat::num_cpus::get().max(1);
+
+                    #threads_block
+                }
+            }
+        }
+        #[cfg(not(feature = "auto"))]
+        None if wants_named_threads(&knobs) => {
+            let single_thread_block =
+                spawn_single_thread(&knobs, quote! { smol_potat::block_on(async { #body_future }) });
+            quote! {
+                #header
+                #outer_attrs
+                fn #fn_name() #ret {
+                    #set_max_blocking_threads
+                    #inner_fn
+
+                    #single_thread_block
+                }
+            }
+        }
+        #[cfg(not(feature = "auto"))]
+        None => quote! {
+            #header
+            #outer_attrs
+            fn #fn_name() #ret {
+                #set_max_blocking_threads
+                #inner_fn
+
+                smol_potat::block_on(async { #body_future })
+            }
+        },
+    };
+
+    Ok(result.into())
+}
@@ -2,6 +2,8 @@
 #![deny(missing_debug_implementations, nonstandard_style)]
 #![recursion_limit = "512"]
 
+mod entry;
+
 use proc_macro::TokenStream;
 use quote::{quote, quote_spanned};
 use syn::spanned::Spanned;
@@ -44,135 +46,66 @@ use syn::spanned::Spanned;
 ///     Ok(())
 /// }
 /// ```
+///
+/// Alternatively, `threads = "auto"` detects the number of threads from
+/// `std::thread::available_parallelism()` at runtime, without requiring the
+/// `auto` Cargo feature:
+///
+/// ```ignore
+/// #[smol_potat::main(threads = "auto")]
+/// async fn main() -> std::io::Result<()> {
+///     Ok(())
+/// }
+/// ```
+///
+/// ## Configuring the Blocking Pool
+///
+/// `max_blocking_threads` bounds the pool smol uses for `unblock`/blocking
+/// I/O, by setting `BLOCKING_MAX_THREADS` if it isn't already set:
+///
+/// ```ignore
+/// #[smol_potat::main(max_blocking_threads = 32)]
+/// async fn main() -> std::io::Result<()> {
+///     Ok(())
+/// }
+/// ```
+///
+/// ## Naming and Sizing Executor Threads
+///
+/// `thread_name` and `thread_stack_size` configure the
+/// `std::thread::Builder` used to spawn each executor thread, which is
+/// useful for profiler output, panic backtraces, and deep-recursion
+/// workloads that overflow the default stack. Executor threads are named
+/// `{thread_name}-0`, `{thread_name}-1`, and so on; when only one thread
+/// runs (no `threads` and no `auto` feature), the entry point runs on a
+/// single thread named `thread_name`. Without `thread_name`, `smol-potat`
+/// is used as the name:
+///
+/// ```ignore
+/// #[smol_potat::main(threads = 4, thread_name = "my-executor", thread_stack_size = 4194304)]
+/// async fn main() -> std::io::Result<()> {
+///     Ok(())
+/// }
+/// ```
 #[cfg(not(test))] // NOTE: exporting main breaks tests, we should file an issue.
 #[proc_macro_attribute]
 pub fn main(attr: TokenStream, item: TokenStream) -> TokenStream {
     let input = syn::parse_macro_input!(item as syn::ItemFn);
     let args = syn::parse_macro_input!(attr as syn::AttributeArgs);
 
-    let ret = &input.sig.output;
-    let inputs = &input.sig.inputs;
-    let name = &input.sig.ident;
-    let body = &input.block;
-    let attrs = &input.attrs;
-    let mut threads = None;
-
-    for arg in args {
-        match arg {
-            syn::NestedMeta::Meta(syn::Meta::NameValue(namevalue)) => {
-                let ident = namevalue.path.get_ident();
-                if ident.is_none() {
-                    return TokenStream::from(quote_spanned! { ident.span() =>
-                        compile_error!("Must have specified ident"),
-                    });
-                }
-                match ident.unwrap().to_string().to_lowercase().as_str() {
-                    "threads" => match &namevalue.lit {
-                        syn::Lit::Int(expr) => {
-                            let num = expr.base10_parse::<u32>().unwrap();
-                            if num > 1 {
-                                threads = Some(num);
-                            }
-                        }
-                        _ => {
-                            return TokenStream::from(quote_spanned! { namevalue.span() =>
-                                compile_error!("threads argument must be an int"),
-                            });
-                        }
-                    },
-                    name => {
-                        return TokenStream::from(quote_spanned! { name.span() =>
-                            compile_error!("Unknown attribute pair {} is specified; expected: `threads`"),
-                        });
-                    }
-                }
-            }
-            other => {
-                return TokenStream::from(quote_spanned! { other.span() =>
-                    compile_error!("Unknown attribute inside the macro"),
-                });
-            }
-        }
+    if input.sig.ident != "main" {
+        return syn::Error::new_spanned(
+            &input.sig.ident,
+            "only the main function can be tagged with #[smol_potat::main]",
+        )
+        .to_compile_error()
+        .into();
     }
 
-    if name != "main" {
-        return TokenStream::from(quote_spanned! { name.span() =>
-            compile_error!("only the main function can be tagged with #[smol::main]"),
-        });
+    match entry::parse_knobs(input, args, false) {
+        Ok(result) => result,
+        Err(e) => e.to_compile_error().into(),
     }
-
-    if input.sig.asyncness.is_none() {
-        return TokenStream::from(quote_spanned! { input.span() =>
-            compile_error!("the async keyword is missing from the function declaration"),
-        });
-    }
-
-    let result = match threads {
-        Some(num) => quote! {
-            fn main() #ret {
-                #(#attrs)*
-                async fn main(#inputs) #ret {
-                    #body
-                }
-
-                let ex = smol_potat::async_executor::Executor::new();
-                let (signal, shutdown) = smol_potat::async_channel::unbounded::<()>();
-
-                let (_, r) = smol_potat::easy_parallel::Parallel::new()
-                    // Run four executor threads.
-                    .each(0..#num, |_| smol_potat::futures_lite::future::block_on(ex.run(shutdown.recv())))
-                    // Run the main future on the current thread.
-                    .finish(|| smol_potat::futures_lite::future::block_on(async {
-                        let r = main().await;
-                        drop(signal);
-                        r
-                    }));
-
-                r
-            }
-        },
-        #[cfg(feature = "auto")]
-        _ => quote! {
-            fn main() #ret {
-                #(#attrs)*
-                async fn main(#inputs) #ret {
-                    #body
-                }
-
-                let ex = smol_potat::async_executor::Executor::new();
-                let (signal, shutdown) = smol_potat::async_channel::unbounded::<()>();
-
-                let num_cpus = smol_potat::num_cpus::get().max(1);
-
-                let (_, r) = smol_potat::easy_parallel::Parallel::new()
-                    // Run four executor threads.
-                    .each(0..num_cpus, |_| smol_potat::futures_lite::future::block_on(ex.run(shutdown.recv())))
-                    // Run the main future on the current thread.
-                    .finish(|| smol_potat::futures_lite::future::block_on(async {
-                        let r = main().await;
-                        drop(signal);
-                        r
-                    }));
-
-                r
-            }
-        },
-        #[cfg(not(feature = "auto"))]
-        _ => quote! {
-            fn main() #ret {
-                #(#attrs)*
-                async fn main(#inputs) #ret {
-                    #body
-                }
-
-                smol_potat::block_on(async {
-                    main().await
-                })
-            }
-        },
-    };
-
-    result.into()
 }
 
 /// Enables an async test function.
@@ -186,30 +119,29 @@ pub fn main(attr: TokenStream, item: TokenStream) -> TokenStream {
 ///     Ok(())
 /// }
 /// ```
+///
+/// ## Manually Configure Threads
+///
+/// Just like `#[smol_potat::main]`, the number of executor threads driving
+/// the test can be set manually, including `threads = "auto"`,
+/// `max_blocking_threads`, `thread_name` and `thread_stack_size`:
+///
+/// ```ignore
+/// #[smol_potat::test(threads=3)]
+/// async fn my_test() -> std::io::Result<()> {
+///     assert_eq!(2 * 2, 4);
+///     Ok(())
+/// }
+/// ```
 #[proc_macro_attribute]
-pub fn test(_attr: TokenStream, item: TokenStream) -> TokenStream {
+pub fn test(attr: TokenStream, item: TokenStream) -> TokenStream {
     let input = syn::parse_macro_input!(item as syn::ItemFn);
+    let args = syn::parse_macro_input!(attr as syn::AttributeArgs);
 
-    let ret = &input.sig.output;
-    let name = &input.sig.ident;
-    let body = &input.block;
-    let attrs = &input.attrs;
-
-    if input.sig.asyncness.is_none() {
-        return TokenStream::from(quote_spanned! { input.span() =>
-            compile_error!("the async keyword is missing from the function declaration"),
-        });
+    match entry::parse_knobs(input, args, true) {
+        Ok(result) => result,
+        Err(e) => e.to_compile_error().into(),
     }
-
-    let result = quote! {
-        #[test]
-        #(#attrs)*
-        fn #name() #ret {
-            smol::block_on(async { #body })
-        }
-    };
-
-    result.into()
 }
 
 /// Enables an async benchmark function.
@@ -225,12 +157,26 @@ pub fn test(_attr: TokenStream, item: TokenStream) -> TokenStream {
 ///     println!("hello world");
 /// }
 /// ```
+///
+/// ## Manually Configure Threads
+///
+/// Just like `#[smol_potat::main]`, the number of executor threads driving
+/// the benchmark can be set manually, including `threads = "auto"`,
+/// `max_blocking_threads`, `thread_name` and `thread_stack_size`:
+///
+/// ```ignore
+/// #[smol_potat::bench(threads=3)]
+/// async fn bench() {
+///     println!("hello world");
+/// }
+/// ```
 #[proc_macro_attribute]
-pub fn bench(_attr: TokenStream, item: TokenStream) -> TokenStream {
+pub fn bench(attr: TokenStream, item: TokenStream) -> TokenStream {
     let input = syn::parse_macro_input!(item as syn::ItemFn);
+    let args = syn::parse_macro_input!(attr as syn::AttributeArgs);
 
     let ret = &input.sig.output;
-    let args = &input.sig.inputs;
+    let inputs = &input.sig.inputs;
     let name = &input.sig.ident;
     let body = &input.block;
     let attrs = &input.attrs;
@@ -241,22 +187,105 @@ pub fn bench(_attr: TokenStream, item: TokenStream) -> TokenStream {
         });
     }
 
-    if !args.is_empty() {
-        return TokenStream::from(quote_spanned! { args.span() =>
+    if !inputs.is_empty() {
+        return TokenStream::from(quote_spanned! { inputs.span() =>
             compile_error!("async benchmarks don't take any arguments"),
         });
     }
 
-    let result = quote! {
-        #[bench]
-        #(#attrs)*
-        fn #name(b: &mut test::Bencher) #ret {
-            let _ = b.iter(|| {
-                smol::block_on(async {
-                    #body
-                })
-            });
+    let knobs = match entry::build_knobs(args) {
+        Ok(knobs) => knobs,
+        Err(e) => return e.to_compile_error().into(),
+    };
+
+    let set_max_blocking_threads = entry::set_max_blocking_threads(&knobs);
+
+    let bench_future = quote! {
+        b.iter(|| { smol_potat::futures_lite::future::block_on(async { #body }) })
+    };
+
+    let result = match knobs.threads {
+        Some(entry::Threads::Fixed(num)) => {
+            let threads_block = entry::spawn_threads(&knobs, quote! { #num }, bench_future.clone());
+            quote! {
+                #[bench]
+                #(#attrs)*
+                fn #name(b: &mut test::Bencher) #ret {
+                    #set_max_blocking_threads
+
+                    let ex = smol_potat::async_executor::Executor::new();
+                    let (signal, shutdown) = smol_potat::async_channel::unbounded::<()>();
+
+                    #threads_block
+                }
+            }
+        }
+        Some(entry::Threads::Auto) => {
+            let threads_block =
+                entry::spawn_threads(&knobs, quote! { auto_threads }, bench_future.clone());
+            quote! {
+                #[bench]
+                #(#attrs)*
+                fn #name(b: &mut test::Bencher) #ret {
+                    #set_max_blocking_threads
+
+                    let ex = smol_potat::async_executor::Executor::new();
+                    let (signal, shutdown) = smol_potat::async_channel::unbounded::<()>();
+
+                    // `threads = "auto"` is resolved entirely through `std`, unlike the
+                    // legacy `auto` Cargo feature below, so it works without relying on
+                    // `smol_potat` re-exporting `num_cpus`.
+                    let auto_threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+
+                    #threads_block
+                }
+            }
         }
+        #[cfg(feature = "auto")]
+        None => {
+            let threads_block = entry::spawn_threads(&knobs, quote! { num_cpus }, bench_future.clone());
+            quote! {
+                #[bench]
+                #(#attrs)*
+                fn #name(b: &mut test::Bencher) #ret {
+                    #set_max_blocking_threads
+
+                    let ex = smol_potat::async_executor::Executor::new();
+                    let (signal, shutdown) = smol_potat::async_channel::unbounded::<()>();
+
+                    let num_cpus = smol_potat::num_cpus::get().max(1);
+
+                    #threads_block
+                }
+            }
+        }
+        #[cfg(not(feature = "auto"))]
+        None if entry::wants_named_threads(&knobs) => {
+            let single_thread_block = entry::spawn_single_thread(&knobs, bench_future.clone());
+            quote! {
+                #[bench]
+                #(#attrs)*
+                fn #name(b: &mut test::Bencher) #ret {
+                    #set_max_blocking_threads
+
+                    let _ = #single_thread_block;
+                }
+            }
+        }
+        #[cfg(not(feature = "auto"))]
+        None => quote! {
+            #[bench]
+            #(#attrs)*
+            fn #name(b: &mut test::Bencher) #ret {
+                #set_max_blocking_threads
+
+                let _ = b.iter(|| {
+                    smol_potat::block_on(async {
+                        #body
+                    })
+                });
+            }
+        },
     };
 
     result.into()
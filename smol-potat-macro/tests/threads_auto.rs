@@ -0,0 +1,14 @@
+//! `threads = "auto"` must compile and run without enabling this crate's
+//! `auto` Cargo feature, since that's the entire point of taking it as an
+//! attribute value instead of a feature flag. Run with `cargo test -p
+//! smol-potat-macro --test threads_auto` (no `--features auto`).
+
+#[smol_potat::test(threads = "auto")]
+async fn auto_threads_without_auto_feature() {
+    assert_eq!(2 + 2, 4);
+}
+
+#[smol_potat::test(threads = "auto", thread_name = "auto-worker")]
+async fn auto_threads_with_thread_name() {
+    assert_eq!(2 + 2, 4);
+}